@@ -12,6 +12,13 @@ use std::{fs, path::PathBuf};
 
 use clap::{Parser, Subcommand};
 
+mod packet_line;
+mod packfile;
+mod repository;
+
+use packet_line::{Packet, PacketLineReader};
+use repository::GitRepository;
+
 #[derive(Parser)]
 struct Cli {
     #[command(subcommand)]
@@ -26,6 +33,8 @@ enum Commands {
     LsTree(LsTreeArgs),
     WriteTree,
     CommitTree(CommitTreeArgs),
+    Clone(CloneArgs),
+    PackObjects(PackObjectsArgs),
 }
 
 #[derive(Debug, Args)]
@@ -58,12 +67,26 @@ struct CommitTreeArgs {
     message: String,
 }
 
+#[derive(Debug, Args)]
+struct CloneArgs {
+    repository: String,
+    directory: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct PackObjectsArgs {
+    output: PathBuf,
+    objects: Vec<GitHash>,
+}
+
 enum GitObject {
     Blob(Blob),
     Tree(Tree),
+    Commit(Commit),
+    Tag(Tag),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct GitHash {
     hash: [u8; 20],
 }
@@ -124,7 +147,7 @@ impl Display for GitHash {
 struct Blob {
     hash: GitHash,
     length: usize,
-    contents: String,
+    contents: Vec<u8>,
 }
 
 #[derive(Debug)]
@@ -138,44 +161,40 @@ impl Tree {
         Self { hash, entries }
     }
 
-    fn from_tree_file(contents: &str) -> Self {
+    /// Each entry is `<mode> <name>\0` followed by a raw 20-byte hash, read
+    /// with `read_exact` so a hash byte that happens to be `0x00` or `0x20`
+    /// can never desynchronize the parse.
+    fn from_tree_file(body: &[u8]) -> Self {
         let hash = {
+            let header = format!("tree {}\0", body.len());
             let mut hasher = Sha1::new();
-            hasher.update(contents.as_bytes());
-            let result = hasher.finalize();
-            GitHash::new(result.into())
+            hasher.update(header.as_bytes());
+            hasher.update(body);
+            GitHash::new(hasher.finalize().into())
         };
 
-        let mut reader = BufReader::new(contents.as_bytes());
-        let mut header = Vec::new();
-        reader.read_until(b'\0', &mut header).unwrap();
-
+        let mut reader = BufReader::new(body);
         let mut entries = Vec::new();
+
         loop {
             let mut file_description = Vec::new();
-            reader.read_until(b'\0', &mut file_description).unwrap();
-
-            let mut hash: [u8; 20] = [0; 20];
-            let read_result = reader.read_exact(&mut hash);
-
-            if read_result.is_err() {
+            let read = reader.read_until(b'\0', &mut file_description).unwrap();
+            if read == 0 {
                 break;
             }
+            file_description.pop(); // drop the trailing NUL
 
-            let file_description = String::from_utf8_lossy(&file_description);
-            let (mode, name) = file_description.trim().split_once(' ').unwrap();
+            let mut entry_hash: [u8; 20] = [0; 20];
+            reader.read_exact(&mut entry_hash).unwrap();
 
-            let entry = TreeEntry {
-                mode: mode.trim_end_matches(char::from(0)).to_string(),
-                hash: GitHash::new(hash),
-                name: name.trim_end_matches(char::from(0)).to_string(),
-            };
-
-            entries.push(entry);
+            let description = String::from_utf8_lossy(&file_description);
+            let (mode, name) = description.split_once(' ').unwrap();
 
-            if reader.buffer().len() < 20 {
-                break;
-            }
+            entries.push(TreeEntry {
+                mode: mode.to_string(),
+                hash: GitHash::new(entry_hash),
+                name: name.to_string(),
+            });
         }
 
         Self::new(hash, entries)
@@ -185,100 +204,96 @@ impl Tree {
         let contents = Self::contents_from_entries(&entries);
 
         let mut hasher = Sha1::new();
-        hasher.update(contents.as_bytes());
+        hasher.update(&contents);
         let result = hasher.finalize();
         let hash = GitHash::new(result.into());
 
         Self { hash, entries }
     }
 
-    fn contents(&self) -> String {
+    fn contents(&self) -> Vec<u8> {
         Self::contents_from_entries(&self.entries)
     }
 
-    fn body_from_entries(entries: &[TreeEntry]) -> String {
-        let mut contents = String::new();
-
-        for entry in entries.iter() {
-            contents.push_str(&format!("{} {}\0", entry.mode, entry.name));
+    fn body(&self) -> Vec<u8> {
+        Self::body_from_entries(&self.entries)
+    }
 
-            let hash_string = unsafe { std::str::from_utf8_unchecked(&entry.hash.hash) };
+    fn body_from_entries(entries: &[TreeEntry]) -> Vec<u8> {
+        let mut body = Vec::new();
 
-            contents.push_str(hash_string);
+        for entry in entries.iter() {
+            body.extend_from_slice(format!("{} {}\0", entry.mode, entry.name).as_bytes());
+            body.extend_from_slice(&entry.hash.hash);
         }
 
-        contents
+        body
     }
 
-    fn contents_from_entries(entries: &[TreeEntry]) -> String {
+    fn contents_from_entries(entries: &[TreeEntry]) -> Vec<u8> {
         let body = Self::body_from_entries(entries);
-        format!("tree {}\0{}", body.len(), body)
+        let mut contents = format!("tree {}\0", body.len()).into_bytes();
+        contents.extend_from_slice(&body);
+        contents
     }
+}
 
-    fn tree_from_directory(path: PathBuf) -> Self {
-        let mut files_and_directories_in_path = fs::read_dir(path).unwrap().collect::<Vec<_>>();
-
-        files_and_directories_in_path.sort_by(|a, b| {
-            let a = a.as_ref().unwrap();
-            let b = b.as_ref().unwrap();
+#[derive(Debug)]
+struct TreeEntry {
+    mode: String,
+    hash: GitHash,
+    name: String,
+}
 
-            a.file_name()
-                .to_str()
-                .unwrap()
-                .cmp(b.file_name().to_str().unwrap())
-        });
+struct Commit {
+    hash: GitHash,
+    contents: Vec<u8>,
+}
 
-        let files_and_directories_in_path = files_and_directories_in_path.iter().filter(|entry| {
-            if let Ok(entry) = entry {
-                entry.file_name().to_str().unwrap() != ".git"
-            } else {
-                false
-            }
-        });
+impl Commit {
+    fn new(hash: GitHash, contents: Vec<u8>) -> Self {
+        Self { hash, contents }
+    }
 
-        let mut entries = Vec::new();
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!("commit {}\0", self.contents.len()).into_bytes();
+        bytes.extend_from_slice(&self.contents);
+        bytes
+    }
 
-        for entry in files_and_directories_in_path {
-            let entry = entry.as_ref().unwrap();
-            let path = entry.path();
-            let metadata = entry.metadata().unwrap();
-
-            if metadata.is_dir() {
-                let tree = Self::tree_from_directory(path);
-                let tree_entry = TreeEntry {
-                    mode: "40000".to_string(),
-                    hash: tree.hash,
-                    name: entry.file_name().to_str().unwrap().to_string(),
-                };
-
-                entries.push(tree_entry);
-            } else {
-                let contents = fs::read_to_string(path).unwrap();
-                let blob = Blob::from_contents(&contents);
-
-                let tree_entry = TreeEntry {
-                    mode: "100644".to_string(),
-                    hash: blob.hash,
-                    name: entry.file_name().to_str().unwrap().to_string(),
-                };
-
-                entries.push(tree_entry);
-            }
-        }
+    fn tree(&self) -> GitHash {
+        let line = self
+            .contents
+            .split(|&b| b == b'\n')
+            .find(|line| line.starts_with(b"tree "))
+            .expect("commit object has no tree line");
 
-        Self::from_entries(entries)
+        std::str::from_utf8(&line[b"tree ".len()..])
+            .unwrap()
+            .parse()
+            .unwrap()
     }
 }
 
-#[derive(Debug)]
-struct TreeEntry {
-    mode: String,
+struct Tag {
     hash: GitHash,
-    name: String,
+    contents: Vec<u8>,
+}
+
+impl Tag {
+    fn new(hash: GitHash, contents: Vec<u8>) -> Self {
+        Self { hash, contents }
+    }
+
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!("tag {}\0", self.contents.len()).into_bytes();
+        bytes.extend_from_slice(&self.contents);
+        bytes
+    }
 }
 
 impl Blob {
-    fn new(hash: GitHash, length: usize, contents: String) -> Self {
+    fn new(hash: GitHash, length: usize, contents: Vec<u8>) -> Self {
         Self {
             hash,
             length,
@@ -286,20 +301,19 @@ impl Blob {
         }
     }
 
-    fn from_contents(contents: &str) -> Self {
+    fn from_contents(contents: &[u8]) -> Self {
         let length = contents.len();
         let header = format!("blob {}\0", length);
-        let store = format!("{}{}", header, contents);
 
         let mut hasher = Sha1::new();
-        hasher.update(store.as_bytes());
-        let result = hasher.finalize();
-        let hash = GitHash::new(result.into());
+        hasher.update(header.as_bytes());
+        hasher.update(contents);
+        let hash = GitHash::new(hasher.finalize().into());
 
         Self {
             hash,
             length,
-            contents: contents.to_string(),
+            contents: contents.to_vec(),
         }
     }
 
@@ -309,7 +323,9 @@ impl Blob {
     }
 
     fn as_bytes(&self) -> Vec<u8> {
-        format!("{}{}", self.header(), self.contents).into_bytes()
+        let mut bytes = self.header().into_bytes();
+        bytes.extend_from_slice(&self.contents);
+        bytes
     }
 }
 
@@ -327,36 +343,90 @@ fn load_git_object_from_hash(hash: GitHash) -> GitObject {
     let mut decompressed = ZlibDecoder::new(&file[..]);
     let mut buf = Vec::new();
     decompressed.read_to_end(&mut buf).unwrap();
-    let contents = String::from_utf8_lossy(&buf);
 
-    let split_contents = contents.split('\0').collect::<Vec<&str>>();
-    let (tipe, length) = split_contents[0].split_once(' ').unwrap();
+    // Only the header is guaranteed ASCII; the body may be arbitrary binary.
+    let header_end = buf.iter().position(|&b| b == b'\0').unwrap();
+    let header = std::str::from_utf8(&buf[..header_end]).unwrap();
+    let (tipe, length) = header.split_once(' ').unwrap();
     let length: usize = length.parse().unwrap();
+    let body = &buf[header_end + 1..header_end + 1 + length];
 
     // TODO: We should homogonise a deserialise/serialize function for each object struct, maybe as trait
     match tipe {
-        "blob" => {
-            let blob = Blob::new(hash, length, split_contents[1][0..length].to_string());
-            GitObject::Blob(blob)
-        }
-        "tree" => GitObject::Tree(Tree::from_tree_file(&contents)),
+        "blob" => GitObject::Blob(Blob::new(hash, length, body.to_vec())),
+        "tree" => GitObject::Tree(Tree::from_tree_file(body)),
+        "commit" => GitObject::Commit(Commit::new(hash, body.to_vec())),
+        "tag" => GitObject::Tag(Tag::new(hash, body.to_vec())),
         _ => panic!("Unknown object type: {}", tipe),
     }
 }
 
+fn write_object_to_disk(object: &GitObject) {
+    let (hash, bytes) = match object {
+        GitObject::Blob(blob) => (blob.hash, blob.as_bytes()),
+        GitObject::Tree(tree) => (tree.hash, tree.contents()),
+        GitObject::Commit(commit) => (commit.hash, commit.as_bytes()),
+        GitObject::Tag(tag) => (tag.hash, tag.as_bytes()),
+    };
+
+    if fs::metadata(hash.path()).is_ok() {
+        return;
+    }
+
+    fs::create_dir_all(hash.dir_path()).unwrap();
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&bytes).unwrap();
+    let compressed = encoder.finish().unwrap();
+    fs::write(hash.path(), compressed).unwrap();
+}
+
+fn checkout_tree(tree_hash: GitHash, into: &std::path::Path) {
+    let tree = match load_git_object_from_hash(tree_hash) {
+        GitObject::Tree(tree) => tree,
+        _ => panic!("expected {} to be a tree", tree_hash),
+    };
+
+    for entry in tree.entries {
+        let path = into.join(&entry.name);
+
+        if entry.mode == "40000" {
+            fs::create_dir_all(&path).unwrap();
+            checkout_tree(entry.hash, &path);
+        } else {
+            let blob = match load_git_object_from_hash(entry.hash) {
+                GitObject::Blob(blob) => blob,
+                _ => panic!("expected {} to be a blob", entry.hash),
+            };
+            fs::write(&path, &blob.contents).unwrap();
+
+            #[cfg(unix)]
+            if entry.mode == "100755" {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            }
+        }
+    }
+}
+
 fn git_cat_file(args: &CatFileArgs) {
     let object = load_git_object_from_hash(args.object);
 
     match object {
         GitObject::Blob(blob) => {
-            print!("{}", blob.contents);
+            std::io::stdout().write_all(&blob.contents).unwrap();
         }
         GitObject::Tree(_) => todo!("git cat-file <tree> needs implementing!"),
+        GitObject::Commit(commit) => {
+            std::io::stdout().write_all(&commit.contents).unwrap();
+        }
+        GitObject::Tag(tag) => {
+            std::io::stdout().write_all(&tag.contents).unwrap();
+        }
     }
 }
 
 fn git_hash_object(args: &HashObjectArgs) {
-    let contents = fs::read_to_string(&args.path).unwrap();
+    let contents = fs::read(&args.path).unwrap();
     let blob = Blob::from_contents(&contents);
 
     if args.write {
@@ -382,20 +452,50 @@ fn git_ls_tree(args: &LsTreeArgs) {
                 println!("{}", entry.name);
             }
         }
+        GitObject::Commit(_) => panic!("git ls-tree <commit> not implemented!"),
+        GitObject::Tag(_) => panic!("git ls-tree <tag> not implemented!"),
     }
 }
 
-fn git_write_tree() {
-    let tree = Tree::tree_from_directory(PathBuf::from("."));
-    fs::create_dir_all(tree.hash.dir_path()).unwrap();
+fn walk_directory_into_repo(repo: &mut GitRepository, path: &PathBuf, path_components: &mut Vec<String>) {
+    let mut entries = fs::read_dir(path).unwrap().collect::<Vec<_>>();
 
-    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
-    encoder.write_all(tree.contents().as_bytes()).unwrap();
+    entries.sort_by(|a, b| {
+        let a = a.as_ref().unwrap();
+        let b = b.as_ref().unwrap();
+        a.file_name().to_str().unwrap().cmp(b.file_name().to_str().unwrap())
+    });
 
-    let compressed = encoder.finish().unwrap();
-    fs::write(tree.hash.path(), compressed).unwrap();
+    for entry in entries {
+        let entry = entry.unwrap();
+        if entry.file_name().to_str().unwrap() == ".git" {
+            continue;
+        }
+
+        let entry_path = entry.path();
+        let name = entry.file_name().to_str().unwrap().to_string();
+
+        if entry.metadata().unwrap().is_dir() {
+            path_components.push(name);
+            walk_directory_into_repo(repo, &entry_path, path_components);
+            path_components.pop();
+        } else {
+            let contents = fs::read(&entry_path).unwrap();
+            repo.insert(path_components, &name, &contents);
+        }
+    }
+}
+
+fn git_write_tree() {
+    let mut repo = GitRepository::new();
+    walk_directory_into_repo(&mut repo, &PathBuf::from("."), &mut Vec::new());
+
+    let (root_hash, objects) = repo.into_objects();
+    for object in &objects {
+        write_object_to_disk(object);
+    }
 
-    println!("{}", tree.hash);
+    println!("{}", root_hash);
 }
 
 fn git_commit_tree(args: &CommitTreeArgs) {
@@ -430,6 +530,118 @@ fn git_commit_tree(args: &CommitTreeArgs) {
     println!("{}", hash);
 }
 
+type AdvertisedRef = (GitHash, String);
+
+fn discover_refs(repository: &str) -> Vec<AdvertisedRef> {
+    let url = format!("{}/info/refs?service=git-upload-pack", repository);
+    let response = ureq::get(&url).call().unwrap();
+
+    let mut body = Vec::new();
+    response.into_reader().read_to_end(&mut body).unwrap();
+
+    PacketLineReader::new(&body)
+        .filter_map(|packet| match packet {
+            Packet::Data(line) => Some(line),
+            _ => None,
+        })
+        .skip(1)
+        .filter_map(|line| {
+            let line = String::from_utf8_lossy(&line).trim_end_matches('\n').to_string();
+            let (hash, name) = line.split_once(' ')?;
+            // The first ref is followed by a NUL-separated capabilities list.
+            let name = name.split('\0').next().unwrap().to_string();
+            Some((hash.parse().ok()?, name))
+        })
+        .collect()
+}
+
+fn resolve_head(refs: &[AdvertisedRef]) -> (GitHash, String) {
+    let head_hash = refs
+        .iter()
+        .find(|(_, name)| name == "HEAD")
+        .map(|(hash, _)| *hash)
+        .expect("remote did not advertise HEAD");
+
+    let branch = refs
+        .iter()
+        .find(|(hash, name)| *hash == head_hash && name.starts_with("refs/heads/"))
+        .map(|(_, name)| name.trim_start_matches("refs/heads/").to_string())
+        .expect("could not resolve HEAD to a branch");
+
+    (head_hash, branch)
+}
+
+fn update_refs(branch: &str, head_hash: GitHash) {
+    fs::write(".git/HEAD", format!("ref: refs/heads/{}\n", branch)).unwrap();
+    fs::create_dir_all(".git/refs/heads").unwrap();
+    fs::write(
+        format!(".git/refs/heads/{}", branch),
+        format!("{}\n", head_hash),
+    )
+    .unwrap();
+}
+
+fn fetch_packfile(repository: &str, wants: &[GitHash]) -> Vec<u8> {
+    let mut request = Vec::new();
+    for want in wants {
+        request.extend_from_slice(&packet_line::encode(format!("want {}\n", want).as_bytes()));
+    }
+    request.extend_from_slice(&packet_line::flush());
+    request.extend_from_slice(&packet_line::encode(b"done\n"));
+
+    let url = format!("{}/git-upload-pack", repository);
+    let response = ureq::post(&url)
+        .set("Content-Type", "application/x-git-upload-pack-request")
+        .send_bytes(&request)
+        .unwrap();
+
+    let mut body = Vec::new();
+    response.into_reader().read_to_end(&mut body).unwrap();
+
+    // The response opens with a single pkt-line, "NAK\n" when there were no
+    // common objects to negotiate, immediately followed by the raw packfile.
+    let mut reader = PacketLineReader::new(&body);
+    reader.next();
+    reader.remaining().to_vec()
+}
+
+fn git_clone(args: &CloneArgs) {
+    let repository = args.repository.trim_end_matches('/');
+    let directory = args.directory.clone().unwrap_or_else(|| {
+        let name = repository.rsplit('/').next().unwrap();
+        PathBuf::from(name.trim_end_matches(".git"))
+    });
+
+    fs::create_dir_all(&directory).unwrap();
+    std::env::set_current_dir(&directory).unwrap();
+    git_init();
+
+    let refs = discover_refs(repository);
+    let (head_hash, branch) = resolve_head(&refs);
+    update_refs(&branch, head_hash);
+
+    let pack_data = fetch_packfile(repository, &[head_hash]);
+    for object in &packfile::parse_packfile(&pack_data) {
+        write_object_to_disk(object);
+    }
+
+    let commit = match load_git_object_from_hash(head_hash) {
+        GitObject::Commit(commit) => commit,
+        _ => panic!("HEAD ({}) did not resolve to a commit", head_hash),
+    };
+    checkout_tree(commit.tree(), &PathBuf::from("."));
+}
+
+fn git_pack_objects(args: &PackObjectsArgs) {
+    let mut pack = packfile::PackFile::new();
+
+    for hash in &args.objects {
+        pack.add_object(&load_git_object_from_hash(*hash));
+    }
+
+    fs::write(&args.output, pack.into_bytes()).unwrap();
+}
+
 fn main() {
     let cli = Cli::parse();
 
@@ -449,5 +661,33 @@ fn main() {
         Commands::CommitTree(args) => {
             git_commit_tree(args);
         }
+        Commands::Clone(args) => {
+            git_clone(args);
+        }
+        Commands::PackObjects(args) => {
+            git_pack_objects(args);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_tree_file_survives_special_hash_bytes() {
+        let mut body = Vec::new();
+        body.extend_from_slice(b"100644 a\0");
+        body.extend_from_slice(&[0x00, 0x20, 0xff, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20]);
+        body.extend_from_slice(b"100644 b\0");
+        body.extend_from_slice(&[1; 20]);
+
+        let tree = Tree::from_tree_file(&body);
+
+        assert_eq!(tree.entries.len(), 2);
+        assert_eq!(tree.entries[0].name, "a");
+        assert_eq!(tree.entries[0].hash.hash[0..3], [0x00, 0x20, 0xff]);
+        assert_eq!(tree.entries[1].name, "b");
+        assert_eq!(tree.entries[1].hash.hash, [1; 20]);
     }
 }
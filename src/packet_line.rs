@@ -0,0 +1,92 @@
+/// The 4-hex-digit length prefix counts itself; `0000`/`0001`/`0002` are the
+/// special flush, delimiter, and response-end markers instead of a length.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Packet {
+    Flush,
+    Delimiter,
+    ResponseEnd,
+    Data(Vec<u8>),
+}
+
+pub(crate) fn encode(payload: &[u8]) -> Vec<u8> {
+    let mut packet = format!("{:04x}", payload.len() + 4).into_bytes();
+    packet.extend_from_slice(payload);
+    packet
+}
+
+pub(crate) fn flush() -> Vec<u8> {
+    b"0000".to_vec()
+}
+
+pub(crate) struct PacketLineReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PacketLineReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub(crate) fn remaining(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+}
+
+impl<'a> Iterator for PacketLineReader<'a> {
+    type Item = Packet;
+
+    fn next(&mut self) -> Option<Packet> {
+        if self.pos + 4 > self.data.len() {
+            return None;
+        }
+
+        let length = std::str::from_utf8(&self.data[self.pos..self.pos + 4]).unwrap();
+        let length = usize::from_str_radix(length, 16).unwrap();
+
+        let packet = match length {
+            0 => Packet::Flush,
+            1 => Packet::Delimiter,
+            2 => Packet::ResponseEnd,
+            _ => Packet::Data(self.data[self.pos + 4..self.pos + length].to_vec()),
+        };
+
+        self.pos += length.max(4);
+        Some(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_flush_delimiter_and_response_end() {
+        let data = b"0000000100020006ab";
+        let packets: Vec<Packet> = PacketLineReader::new(data).collect();
+        assert_eq!(packets, vec![Packet::Flush, Packet::Delimiter, Packet::ResponseEnd, Packet::Data(b"ab".to_vec())]);
+    }
+
+    #[test]
+    fn reads_data_packet() {
+        let data = b"0009want\n";
+        let packets: Vec<Packet> = PacketLineReader::new(data).collect();
+        assert_eq!(packets, vec![Packet::Data(b"want\n".to_vec())]);
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let mut buf = encode(b"hello");
+        buf.extend_from_slice(&flush());
+        let packets: Vec<Packet> = PacketLineReader::new(&buf).collect();
+        assert_eq!(packets, vec![Packet::Data(b"hello".to_vec()), Packet::Flush]);
+    }
+
+    #[test]
+    fn remaining_tracks_bytes_left_unread() {
+        let data = b"0009want\nextra";
+        let mut reader = PacketLineReader::new(data);
+        reader.next();
+        assert_eq!(reader.remaining(), b"extra");
+    }
+}
@@ -0,0 +1,112 @@
+use indexmap::IndexMap;
+
+use crate::{Blob, GitHash, GitObject, Tree, TreeEntry};
+
+enum TreeItem {
+    Blob(GitHash),
+    Directory(Directory),
+}
+
+#[derive(Default)]
+struct Directory {
+    entries: IndexMap<String, TreeItem>,
+}
+
+impl Directory {
+    fn new() -> Self {
+        Self { entries: IndexMap::new() }
+    }
+
+    fn insert(&mut self, path_components: &[String], filename: &str, hash: GitHash) {
+        match path_components.split_first() {
+            None => {
+                self.entries.insert(filename.to_string(), TreeItem::Blob(hash));
+            }
+            Some((first, rest)) => {
+                let item = self
+                    .entries
+                    .entry(first.clone())
+                    .or_insert_with(|| TreeItem::Directory(Directory::new()));
+
+                match item {
+                    TreeItem::Directory(directory) => directory.insert(rest, filename, hash),
+                    TreeItem::Blob(_) => panic!("{} already exists as a file", first),
+                }
+            }
+        }
+    }
+}
+
+pub(crate) struct GitRepository {
+    objects: IndexMap<GitHash, GitObject>,
+    root: Directory,
+}
+
+impl GitRepository {
+    pub(crate) fn new() -> Self {
+        Self { objects: IndexMap::new(), root: Directory::new() }
+    }
+
+    pub(crate) fn insert(&mut self, path_components: &[String], filename: &str, content: &[u8]) {
+        let blob = Blob::from_contents(content);
+        let hash = blob.hash;
+        self.objects.insert(hash, GitObject::Blob(blob));
+        self.root.insert(path_components, filename, hash);
+    }
+
+    pub(crate) fn into_objects(mut self) -> (GitHash, Vec<GitObject>) {
+        let root_hash = hash_directory(&self.root, &mut self.objects);
+        (root_hash, self.objects.into_values().collect())
+    }
+}
+
+fn hash_directory(directory: &Directory, objects: &mut IndexMap<GitHash, GitObject>) -> GitHash {
+    let mut entries: Vec<TreeEntry> = directory
+        .entries
+        .iter()
+        .map(|(name, item)| {
+            let (mode, hash) = match item {
+                TreeItem::Blob(hash) => ("100644", *hash),
+                TreeItem::Directory(subdirectory) => ("40000", hash_directory(subdirectory, objects)),
+            };
+
+            TreeEntry { mode: mode.to_string(), hash, name: name.clone() }
+        })
+        .collect();
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let tree = Tree::from_entries(entries);
+    let hash = tree.hash;
+    objects.entry(hash).or_insert(GitObject::Tree(tree));
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dedupes_identical_subtrees_by_hash() {
+        let mut repo = GitRepository::new();
+        repo.insert(&["a".to_string()], "file.txt", b"hello");
+        repo.insert(&["b".to_string()], "file.txt", b"hello");
+
+        let (root_hash, objects) = repo.into_objects();
+
+        let tree_count = objects.iter().filter(|o| matches!(o, GitObject::Tree(_))).count();
+        assert_eq!(tree_count, 2, "the two identical subtrees should collapse into one");
+        assert_eq!(objects.len(), 3, "one blob, one deduped subtree, one root tree");
+
+        let root = objects
+            .iter()
+            .find_map(|o| match o {
+                GitObject::Tree(tree) if tree.hash == root_hash => Some(tree),
+                _ => None,
+            })
+            .expect("root tree must be present in the returned objects");
+        assert_eq!(root.entries.len(), 2);
+        assert_eq!(root.entries[0].hash, root.entries[1].hash, "both subtrees hash the same");
+    }
+}